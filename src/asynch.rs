@@ -0,0 +1,219 @@
+//! An async variant of the SOCKS4 client.
+//!
+//! This module is gated behind the `async` feature. It performs the same
+//! SOCKS4/4A handshake as the [`v4`](../v4/index.html) module, but over any
+//! `AsyncRead + AsyncWrite` stream, so it can run on top of either an
+//! `async-std` or a `tokio` reactor without blocking a thread per connection.
+//!
+//! Establishing that stream is runtime-specific, so only `connect_with_socket`/
+//! `bind_with_socket` are provided generically. The `async-std` feature adds
+//! runtime-appropriate `connect`/`bind` on top, since `async_std::net::TcpStream`
+//! already implements the `futures` `AsyncRead`/`AsyncWrite` traits used here.
+//! `tokio`'s `TcpStream` implements its own, incompatible I/O traits, so a
+//! tokio caller needs to wrap one (e.g. with `tokio_util::compat`) and hand it
+//! to `connect_with_socket`/`bind_with_socket` instead.
+
+use byteorder::BigEndian;
+use futures_util::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use crate::{TargetAddr, ToTargetAddr};
+
+async fn read_response<R: AsyncRead + Unpin>(socket: &mut R) -> io::Result<SocketAddrV4> {
+    let mut response = [0u8; 8];
+    socket.read_exact(&mut response).await?;
+
+    if response[0] != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response version"));
+    }
+
+    match response[1] {
+        90 => {}
+        91 => return Err(io::Error::new(io::ErrorKind::Other, "request rejected or failed")),
+        92 => {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      "request rejected because SOCKS server cannot connect to \
+                                       idnetd on the client"))
+        }
+        93 => {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      "request rejected because the client program and identd \
+                                       report different user-ids"))
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response code")),
+    }
+
+    let port = u16::from_be_bytes([response[2], response[3]]);
+    let ip = Ipv4Addr::from(u32::from_be_bytes([response[4], response[5], response[6], response[7]]));
+
+    Ok(SocketAddrV4::new(ip, port))
+}
+
+fn request_packet(command: u8, target: &TargetAddr, userid: &str) -> io::Result<Vec<u8>> {
+    use byteorder::WriteBytesExt;
+
+    let mut packet = vec![];
+    let _ = packet.write_u8(4); // version
+    let _ = packet.write_u8(command); // command code
+    match *target {
+        TargetAddr::Ip(addr) => {
+            let addr = match addr {
+                SocketAddr::V4(addr) => addr,
+                SocketAddr::V6(_) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "SOCKS4 does not support IPv6"));
+                }
+            };
+            let _ = packet.write_u16::<BigEndian>(addr.port());
+            let _ = packet.write_u32::<BigEndian>((*addr.ip()).into());
+            let _ = packet.write_all(userid.as_bytes());
+            let _ = packet.write_u8(0);
+        }
+        TargetAddr::Domain(ref host, port) => {
+            let _ = packet.write_u16::<BigEndian>(port);
+            let _ = packet.write_u32::<BigEndian>(Ipv4Addr::new(0, 0, 0, 1).into());
+            let _ = packet.write_all(userid.as_bytes());
+            let _ = packet.write_u8(0);
+            let _ = packet.extend(host.as_bytes());
+            let _ = packet.write_u8(0);
+        }
+    }
+
+    Ok(packet)
+}
+
+/// An async SOCKS4 client.
+#[derive(Debug)]
+pub struct Socks4Stream<S> {
+    socket: S,
+    proxy_addr: SocketAddrV4,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Socks4Stream<S> {
+    /// Connects to a target server through a SOCKS4 proxy, performing the
+    /// handshake over an already-established async stream.
+    ///
+    /// # Note
+    ///
+    /// If `target` is a `TargetAddr::Domain`, the domain name will be forwarded
+    /// to the proxy server using the SOCKS4A protocol extension.
+    pub async fn connect_with_socket<U>(socket: S, target: U, userid: &str) -> io::Result<Socks4Stream<S>>
+        where U: ToTargetAddr
+    {
+        Self::handshake(1, socket, target, userid).await
+    }
+
+    async fn handshake<U>(command: u8, mut socket: S, target: U, userid: &str) -> io::Result<Socks4Stream<S>>
+        where U: ToTargetAddr
+    {
+        let target = target.to_target_addr()?;
+        let packet = request_packet(command, &target, userid)?;
+
+        socket.write_all(&packet).await?;
+        let proxy_addr = read_response(&mut socket).await?;
+
+        Ok(Socks4Stream {
+            socket: socket,
+            proxy_addr: proxy_addr,
+        })
+    }
+
+    /// Returns the proxy-side address of the connection between the proxy and
+    /// target server.
+    pub fn proxy_addr(&self) -> SocketAddrV4 {
+        self.proxy_addr
+    }
+
+    /// Returns a shared reference to the inner stream.
+    pub fn get_ref(&self) -> &S {
+        &self.socket
+    }
+
+    /// Returns a mutable reference to the inner stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.socket
+    }
+
+    /// Consumes the `Socks4Stream`, returning the inner stream.
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Socks4Stream<S> {
+    fn poll_read(self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context,
+                 buf: &mut [u8]) -> ::std::task::Poll<io::Result<usize>> {
+        ::std::pin::Pin::new(&mut self.get_mut().socket).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Socks4Stream<S> {
+    fn poll_write(self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context,
+                  buf: &[u8]) -> ::std::task::Poll<io::Result<usize>> {
+        ::std::pin::Pin::new(&mut self.get_mut().socket).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context)
+                  -> ::std::task::Poll<io::Result<()>> {
+        ::std::pin::Pin::new(&mut self.get_mut().socket).poll_flush(cx)
+    }
+
+    fn poll_close(self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context)
+                  -> ::std::task::Poll<io::Result<()>> {
+        ::std::pin::Pin::new(&mut self.get_mut().socket).poll_close(cx)
+    }
+}
+
+/// An async SOCKS4 BIND client.
+#[derive(Debug)]
+pub struct Socks4Listener<S>(Socks4Stream<S>);
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Socks4Listener<S> {
+    /// Initiates a SOCKS4 BIND request over an already-established async
+    /// stream.
+    pub async fn bind_with_socket<U>(socket: S, target: U, userid: &str) -> io::Result<Socks4Listener<S>>
+        where U: ToTargetAddr
+    {
+        Socks4Stream::handshake(2, socket, target, userid).await.map(Socks4Listener)
+    }
+
+    /// Waits for the remote server to connect to the proxy-side socket,
+    /// returning a stream once it does.
+    pub async fn accept(mut self) -> io::Result<Socks4Stream<S>> {
+        self.0.proxy_addr = read_response(&mut self.0.socket).await?;
+        Ok(self.0)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl Socks4Stream<::async_std::net::TcpStream> {
+    /// Connects to a target server through a SOCKS4 proxy over TCP, using an
+    /// `async-std` reactor.
+    pub async fn connect<T, U>(proxy: T,
+                               target: U,
+                               userid: &str)
+                               -> io::Result<Socks4Stream<::async_std::net::TcpStream>>
+        where T: ::async_std::net::ToSocketAddrs,
+              U: ToTargetAddr
+    {
+        let socket = ::async_std::net::TcpStream::connect(proxy).await?;
+        Self::handshake(1, socket, target, userid).await
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl Socks4Listener<::async_std::net::TcpStream> {
+    /// Initiates a SOCKS4 BIND request to the specified proxy over TCP,
+    /// using an `async-std` reactor.
+    pub async fn bind<T, U>(proxy: T,
+                            target: U,
+                            userid: &str)
+                            -> io::Result<Socks4Listener<::async_std::net::TcpStream>>
+        where T: ::async_std::net::ToSocketAddrs,
+              U: ToTargetAddr
+    {
+        let socket = ::async_std::net::TcpStream::connect(proxy).await?;
+        Socks4Stream::handshake(2, socket, target, userid).await.map(Socks4Listener)
+    }
+}
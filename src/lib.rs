@@ -0,0 +1,120 @@
+//! SOCKS proxy clients
+//!
+//! This crate requires edition 2018 (set via `edition = "2018"` in
+//! `Cargo.toml`): the `asynch` module needs `async fn`, which edition 2015
+//! rejects, so the `?` operator is used crate-wide instead of the `try!`
+//! macro for consistency.
+#![warn(missing_docs)]
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+#[cfg(feature = "async")]
+mod asynch;
+mod v4;
+mod v5;
+
+#[cfg(feature = "async")]
+pub use asynch::{Socks4Stream as AsyncSocks4Stream, Socks4Listener as AsyncSocks4Listener};
+pub use v4::{Socks4Listener, Socks4Stream};
+pub use v5::{Socks5Datagram, Socks5Stream};
+
+/// A description of a connection target.
+#[derive(Debug, Clone)]
+pub enum TargetAddr {
+    /// Connect to an IP address.
+    Ip(SocketAddr),
+    /// Connect to a fully qualified domain name.
+    ///
+    /// The domain name will be passed along to the proxy server and DNS lookup
+    /// will happen there.
+    Domain(String, u16),
+}
+
+/// A trait for objects that can be converted to `TargetAddr`.
+pub trait ToTargetAddr {
+    /// Converts the value of `self` to a `TargetAddr`.
+    fn to_target_addr(&self) -> io::Result<TargetAddr>;
+}
+
+impl ToTargetAddr for TargetAddr {
+    fn to_target_addr(&self) -> io::Result<TargetAddr> {
+        Ok(self.clone())
+    }
+}
+
+impl ToTargetAddr for SocketAddr {
+    fn to_target_addr(&self) -> io::Result<TargetAddr> {
+        Ok(TargetAddr::Ip(*self))
+    }
+}
+
+impl ToTargetAddr for SocketAddrV4 {
+    fn to_target_addr(&self) -> io::Result<TargetAddr> {
+        SocketAddr::V4(*self).to_target_addr()
+    }
+}
+
+impl ToTargetAddr for SocketAddrV6 {
+    fn to_target_addr(&self) -> io::Result<TargetAddr> {
+        SocketAddr::V6(*self).to_target_addr()
+    }
+}
+
+impl ToTargetAddr for (Ipv4Addr, u16) {
+    fn to_target_addr(&self) -> io::Result<TargetAddr> {
+        SocketAddrV4::new(self.0, self.1).to_target_addr()
+    }
+}
+
+impl ToTargetAddr for (Ipv6Addr, u16) {
+    fn to_target_addr(&self) -> io::Result<TargetAddr> {
+        SocketAddrV6::new(self.0, self.1, 0, 0).to_target_addr()
+    }
+}
+
+impl<'a> ToTargetAddr for (&'a str, u16) {
+    fn to_target_addr(&self) -> io::Result<TargetAddr> {
+        // a v3 onion hostname must never hit the local resolver: that would
+        // either fail outright or leak the hidden service address to
+        // whatever DNS server the host is configured to use. Reject anything
+        // that merely ends in `.onion` but fails the v3 format check, rather
+        // than silently falling through to a plain domain lookup.
+        if self.0.ends_with(".onion") {
+            if is_onion_v3(self.0) {
+                return Ok(TargetAddr::Domain(self.0.to_owned(), self.1));
+            }
+
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "not a valid v3 .onion address"));
+        }
+
+        // try to parse as an IP first
+        if let Ok(addr) = self.0.parse::<Ipv4Addr>() {
+            return (addr, self.1).to_target_addr();
+        }
+
+        if let Ok(addr) = self.0.parse::<Ipv6Addr>() {
+            return (addr, self.1).to_target_addr();
+        }
+
+        Ok(TargetAddr::Domain(self.0.to_owned(), self.1))
+    }
+}
+
+/// Returns `true` if `host` looks like a Tor v3 `.onion` hostname: a
+/// 56-character base32 (`a`-`z`, `2`-`7`) label followed by the `.onion`
+/// suffix.
+fn is_onion_v3(host: &str) -> bool {
+    const LABEL_LEN: usize = 56;
+    const SUFFIX: &'static str = ".onion";
+
+    if host.len() != LABEL_LEN + SUFFIX.len() || !host.ends_with(SUFFIX) {
+        return false;
+    }
+
+    host[..LABEL_LEN].bytes().all(|b| match b {
+        b'a'..=b'z' | b'2'..=b'7' => true,
+        _ => false,
+    })
+}
@@ -1,19 +1,20 @@
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 use std::io::{self, Read, Write};
 use std::net::{SocketAddr, ToSocketAddrs, SocketAddrV4, SocketAddrV6, TcpStream, Ipv4Addr};
+use std::time::Duration;
 
-use {ToTargetAddr, TargetAddr};
+use crate::{ToTargetAddr, TargetAddr};
 
-fn read_response(socket: &mut TcpStream) -> io::Result<SocketAddrV4> {
+fn read_response<R: Read>(socket: &mut R) -> io::Result<SocketAddrV4> {
     let mut response = [0u8; 8];
-    try!(socket.read_exact(&mut response));
+    socket.read_exact(&mut response)?;
     let mut response = &response[..];
 
-    if try!(response.read_u8()) != 0 {
+    if response.read_u8()? != 0 {
         return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response version"));
     }
 
-    match try!(response.read_u8()) {
+    match response.read_u8()? {
         90 => {}
         91 => return Err(io::Error::new(io::ErrorKind::Other, "request rejected or failed")),
         92 => {
@@ -29,21 +30,32 @@ fn read_response(socket: &mut TcpStream) -> io::Result<SocketAddrV4> {
         _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response code")),
     }
 
-    let port = try!(response.read_u16::<BigEndian>());
-    let ip = Ipv4Addr::from(try!(response.read_u32::<BigEndian>()));
+    let port = response.read_u16::<BigEndian>()?;
+    let ip = Ipv4Addr::from(response.read_u32::<BigEndian>()?);
 
     Ok(SocketAddrV4::new(ip, port))
 }
 
 /// A SOCKS4 client.
+///
+/// `Socks4Stream` is generic over the underlying transport `S` so that the
+/// handshake can run over anything that implements `Read + Write`, not just
+/// a `TcpStream`. The common case of connecting over TCP is covered by
+/// `Socks4Stream::<TcpStream>::connect`; `connect_with_socket` performs only
+/// the handshake over an already-established stream (a TLS session, an
+/// in-memory pipe, or some other non-`TcpStream` transport).
 #[derive(Debug)]
-pub struct Socks4Stream {
-    socket: TcpStream,
+pub struct Socks4Stream<S = TcpStream> {
+    socket: S,
     proxy_addr: SocketAddrV4,
 }
 
-impl Socks4Stream {
-    /// Connects to a target server through a SOCKS4 proxy.
+impl<S: Read + Write> Socks4Stream<S> {
+    /// Performs the SOCKS4/4A handshake over an already-established stream.
+    ///
+    /// This does not attempt to establish `socket` itself; it only speaks
+    /// the SOCKS4 protocol over it. Useful for running the handshake over
+    /// a transport other than a raw `TcpStream`.
     ///
     /// # Note
     ///
@@ -51,25 +63,21 @@ impl Socks4Stream {
     /// to the proxy server using the SOCKS4A protocol extension. If the proxy
     /// server does not support SOCKS4A, consider performing the DNS lookup
     /// locally and passing a `TargetAddr::Ip`.
-    pub fn connect<T, U>(proxy: T, target: U, userid: &str) -> io::Result<Socks4Stream>
-        where T: ToSocketAddrs,
-              U: ToTargetAddr
+    pub fn connect_with_socket<U>(socket: S, target: U, userid: &str) -> io::Result<Socks4Stream<S>>
+        where U: ToTargetAddr
     {
-        Self::connect_raw(1, proxy, target, userid)
+        Self::handshake(1, socket, target, userid)
     }
 
-    fn connect_raw<T, U>(command: u8, proxy: T, target: U, userid: &str) -> io::Result<Socks4Stream>
-        where T: ToSocketAddrs,
-              U: ToTargetAddr
+    fn handshake<U>(command: u8, mut socket: S, target: U, userid: &str) -> io::Result<Socks4Stream<S>>
+        where U: ToTargetAddr
     {
-        let mut socket = try!(TcpStream::connect(proxy));
-
-        let target = try!(target.to_target_addr());
+        let target = target.to_target_addr()?;
 
         let mut packet = vec![];
         let _ = packet.write_u8(4); // version
         let _ = packet.write_u8(command); // command code
-        match try!(target.to_target_addr()) {
+        match target {
             TargetAddr::Ip(addr) => {
                 let addr = match addr {
                     SocketAddr::V4(addr) => addr,
@@ -93,50 +101,107 @@ impl Socks4Stream {
             }
         }
 
-        try!(socket.write_all(&packet));
-        let proxy_addr = try!(read_response(&mut socket));
+        socket.write_all(&packet)?;
+        let proxy_addr = read_response(&mut socket)?;
 
         Ok(Socks4Stream {
             socket: socket,
             proxy_addr: proxy_addr,
         })
     }
+}
+
+impl Socks4Stream<TcpStream> {
+    /// Connects to a target server through a SOCKS4 proxy over TCP.
+    ///
+    /// # Note
+    ///
+    /// If `target` is a `TargetAddr::Domain`, the domain name will be forwarded
+    /// to the proxy server using the SOCKS4A protocol extension. If the proxy
+    /// server does not support SOCKS4A, consider performing the DNS lookup
+    /// locally and passing a `TargetAddr::Ip`.
+    pub fn connect<T, U>(proxy: T, target: U, userid: &str) -> io::Result<Socks4Stream<TcpStream>>
+        where T: ToSocketAddrs,
+              U: ToTargetAddr
+    {
+        let socket = TcpStream::connect(proxy)?;
+        Self::handshake(1, socket, target, userid)
+    }
+
+    /// Connects to a target server through a SOCKS4 proxy over TCP, bounding
+    /// both the TCP connection and the SOCKS4 reply read by `timeout`.
+    ///
+    /// The read/write timeouts are cleared from the resulting stream once the
+    /// handshake completes, so ordinary traffic through the connection is not
+    /// bound by `timeout`.
+    pub fn connect_timeout<U>(proxy: SocketAddr,
+                              target: U,
+                              userid: &str,
+                              timeout: Duration)
+                              -> io::Result<Socks4Stream<TcpStream>>
+        where U: ToTargetAddr
+    {
+        let socket = TcpStream::connect_timeout(&proxy, timeout)?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+
+        let stream = Self::handshake(1, socket, target, userid)?;
+
+        stream.socket.set_read_timeout(None)?;
+        stream.socket.set_write_timeout(None)?;
+
+        Ok(stream)
+    }
+
+    /// Sets the read timeout on the underlying stream.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
 
+    /// Sets the write timeout on the underlying stream.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_write_timeout(timeout)
+    }
+}
+
+impl<S> Socks4Stream<S> {
     /// Returns the proxy-side address of the connection between the proxy and
     /// target server.
     pub fn proxy_addr(&self) -> SocketAddrV4 {
         self.proxy_addr
     }
 
-    /// Returns a shared reference to the inner `TcpStream`.
-    pub fn get_ref(&self) -> &TcpStream {
+    /// Returns a shared reference to the inner stream.
+    pub fn get_ref(&self) -> &S {
         &self.socket
     }
 
-    /// Returns a mutable reference to the inner `TcpStream`.
-    pub fn get_mut(&mut self) -> &mut TcpStream {
+    /// Returns a mutable reference to the inner stream.
+    pub fn get_mut(&mut self) -> &mut S {
         &mut self.socket
     }
 
-    /// Consumes the `Socks4Stream`, returning the inner `TcpStream`.
-    pub fn into_inner(self) -> TcpStream {
+    /// Consumes the `Socks4Stream`, returning the inner stream.
+    pub fn into_inner(self) -> S {
         self.socket
     }
 }
 
-impl Read for Socks4Stream {
+impl<S: Read> Read for Socks4Stream<S> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.socket.read(buf)
     }
 }
 
-impl<'a> Read for &'a Socks4Stream {
+impl<'a, S> Read for &'a Socks4Stream<S>
+    where &'a S: Read
+{
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         (&self.socket).read(buf)
     }
 }
 
-impl Write for Socks4Stream {
+impl<S: Write> Write for Socks4Stream<S> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.socket.write(buf)
     }
@@ -146,7 +211,9 @@ impl Write for Socks4Stream {
     }
 }
 
-impl<'a> Write for &'a Socks4Stream {
+impl<'a, S> Write for &'a Socks4Stream<S>
+    where &'a S: Write
+{
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         (&self.socket).write(buf)
     }
@@ -156,32 +223,76 @@ impl<'a> Write for &'a Socks4Stream {
     }
 }
 
+/// A SOCKS4 BIND client.
 #[derive(Debug)]
-pub struct Socks4Listener(Socks4Stream);
+pub struct Socks4Listener<S = TcpStream>(Socks4Stream<S>);
 
-impl Socks4Listener {
-    pub fn bind<T, U>(proxy: T, target: U, userid: &str) -> io::Result<Socks4Listener>
+impl<S: Read + Write> Socks4Listener<S> {
+    /// Initiates a SOCKS4 BIND request over an already-established stream.
+    ///
+    /// See `Socks4Stream::connect_with_socket` for the rationale; this is the
+    /// counterpart of `Socks4Listener::bind` for an already-established stream.
+    pub fn bind_with_socket<U>(socket: S, target: U, userid: &str) -> io::Result<Socks4Listener<S>>
+        where U: ToTargetAddr
+    {
+        Socks4Stream::handshake(2, socket, target, userid).map(Socks4Listener)
+    }
+}
+
+impl<S: Read> Socks4Listener<S> {
+    /// Waits for the remote server to connect to the proxy-side socket,
+    /// returning a stream once it does.
+    pub fn accept(mut self) -> io::Result<Socks4Stream<S>> {
+        self.0.proxy_addr = read_response(&mut self.0.socket)?;
+        Ok(self.0)
+    }
+}
+
+impl Socks4Listener<TcpStream> {
+    /// Initiates a SOCKS4 BIND request to the specified proxy over TCP.
+    ///
+    /// The proxy will filter incoming connections based on `target`.
+    pub fn bind<T, U>(proxy: T, target: U, userid: &str) -> io::Result<Socks4Listener<TcpStream>>
         where T: ToSocketAddrs,
               U: ToTargetAddr
     {
-        Socks4Stream::connect_raw(2, proxy, target, userid).map(Socks4Listener)
+        let socket = TcpStream::connect(proxy)?;
+        Socks4Stream::handshake(2, socket, target, userid).map(Socks4Listener)
+    }
+
+    /// Initiates a SOCKS4 BIND request to the specified proxy over TCP,
+    /// bounding the TCP connection and the first SOCKS4 reply read by
+    /// `timeout`.
+    ///
+    /// Unlike `Socks4Stream::connect_timeout`, the read/write timeouts are
+    /// left configured on the socket after the handshake completes, so the
+    /// second reply read performed by `accept` honors the same deadline.
+    pub fn bind_timeout<U>(proxy: SocketAddr,
+                           target: U,
+                           userid: &str,
+                           timeout: Duration)
+                           -> io::Result<Socks4Listener<TcpStream>>
+        where U: ToTargetAddr
+    {
+        let socket = TcpStream::connect_timeout(&proxy, timeout)?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+
+        Socks4Stream::handshake(2, socket, target, userid).map(Socks4Listener)
     }
 
+    /// Returns the address of the proxy-side socket that will accept the
+    /// remote connection.
     pub fn proxy_addr(&self) -> io::Result<SocketAddr> {
         if self.0.proxy_addr.ip().octets() != [0, 0, 0, 0] {
             Ok(SocketAddr::V4(self.0.proxy_addr()))
         } else {
             let port = self.0.proxy_addr.port();
-            let peer = match try!(self.0.socket.peer_addr()) {
+            let peer = match self.0.socket.peer_addr()? {
                 SocketAddr::V4(addr) => SocketAddr::V4(SocketAddrV4::new(*addr.ip(), port)),
                 SocketAddr::V6(addr) => SocketAddr::V6(SocketAddrV6::new(*addr.ip(), port, 0, 0)),
             };
             Ok(peer)
         }
     }
-
-    pub fn accept(mut self) -> io::Result<Socks4Stream> {
-        self.0.proxy_addr = try!(read_response(&mut self.0.socket));
-        Ok(self.0)
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,385 @@
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs, SocketAddrV4, SocketAddrV6, TcpStream, UdpSocket, Ipv4Addr,
+               Ipv6Addr};
+
+use crate::{TargetAddr, ToTargetAddr};
+
+const SOCKS_VERSION: u8 = 5;
+
+const METHOD_NO_AUTH: u8 = 0;
+const METHOD_PASSWORD: u8 = 2;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+const CMD_CONNECT: u8 = 1;
+const CMD_UDP_ASSOCIATE: u8 = 3;
+
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+enum Authentication<'a> {
+    None,
+    Password {
+        username: &'a str,
+        password: &'a str,
+    },
+}
+
+impl<'a> Authentication<'a> {
+    fn method(&self) -> u8 {
+        match *self {
+            Authentication::None => METHOD_NO_AUTH,
+            Authentication::Password { .. } => METHOD_PASSWORD,
+        }
+    }
+}
+
+fn read_address<R: Read>(socket: &mut R) -> io::Result<TargetAddr> {
+    match socket.read_u8()? {
+        ATYP_IPV4 => {
+            let ip = Ipv4Addr::from(socket.read_u32::<BigEndian>()?);
+            let port = socket.read_u16::<BigEndian>()?;
+            Ok(TargetAddr::Ip(SocketAddr::V4(SocketAddrV4::new(ip, port))))
+        }
+        ATYP_IPV6 => {
+            let mut raw = [0u8; 16];
+            socket.read_exact(&mut raw)?;
+            let port = socket.read_u16::<BigEndian>()?;
+            Ok(TargetAddr::Ip(SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(raw), port, 0, 0))))
+        }
+        ATYP_DOMAIN => {
+            let len = socket.read_u8()? as usize;
+            let mut domain = vec![0u8; len];
+            socket.read_exact(&mut domain)?;
+            let domain = String::from_utf8(domain)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid domain name"))?;
+            let port = socket.read_u16::<BigEndian>()?;
+            Ok(TargetAddr::Domain(domain, port))
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid address type")),
+    }
+}
+
+fn write_address<W: Write>(socket: &mut W, target: &TargetAddr) -> io::Result<()> {
+    match *target {
+        TargetAddr::Ip(SocketAddr::V4(addr)) => {
+            socket.write_u8(ATYP_IPV4)?;
+            socket.write_u32::<BigEndian>((*addr.ip()).into())?;
+            socket.write_u16::<BigEndian>(addr.port())?;
+        }
+        TargetAddr::Ip(SocketAddr::V6(addr)) => {
+            socket.write_u8(ATYP_IPV6)?;
+            socket.write_all(&addr.ip().octets())?;
+            socket.write_u16::<BigEndian>(addr.port())?;
+        }
+        TargetAddr::Domain(ref domain, port) => {
+            if domain.len() > 255 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "domain name too long"));
+            }
+            socket.write_u8(ATYP_DOMAIN)?;
+            socket.write_u8(domain.len() as u8)?;
+            socket.write_all(domain.as_bytes())?;
+            socket.write_u16::<BigEndian>(port)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn negotiate_auth(socket: &mut TcpStream, auth: &Authentication) -> io::Result<()> {
+    socket.write_all(&[SOCKS_VERSION, 1, auth.method()])?;
+
+    let mut response = [0u8; 2];
+    socket.read_exact(&mut response)?;
+    if response[0] != SOCKS_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response version"));
+    }
+    if response[1] == METHOD_NONE_ACCEPTABLE {
+        return Err(io::Error::new(io::ErrorKind::Other, "no acceptable auth methods"));
+    }
+    if response[1] != auth.method() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected auth method"));
+    }
+
+    if let Authentication::Password { username, password } = *auth {
+        let mut packet = vec![];
+        let _ = packet.write_u8(1); // sub-negotiation version
+        let _ = packet.write_u8(username.len() as u8);
+        let _ = packet.write_all(username.as_bytes());
+        let _ = packet.write_u8(password.len() as u8);
+        let _ = packet.write_all(password.as_bytes());
+        socket.write_all(&packet)?;
+
+        let mut response = [0u8; 2];
+        socket.read_exact(&mut response)?;
+        if response[1] != 0 {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      "username/password authentication failed"));
+        }
+    }
+
+    Ok(())
+}
+
+fn request<U>(socket: &mut TcpStream, command: u8, target: U, auth: &Authentication) -> io::Result<TargetAddr>
+    where U: ToTargetAddr
+{
+    negotiate_auth(socket, auth)?;
+
+    let target = target.to_target_addr()?;
+
+    let mut packet = vec![];
+    let _ = packet.write_u8(SOCKS_VERSION);
+    let _ = packet.write_u8(command);
+    let _ = packet.write_u8(0); // reserved
+    write_address(&mut packet, &target)?;
+
+    socket.write_all(&packet)?;
+
+    let mut header = [0u8; 3];
+    socket.read_exact(&mut header)?;
+    if header[0] != SOCKS_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response version"));
+    }
+    match header[1] {
+        0 => {}
+        1 => return Err(io::Error::new(io::ErrorKind::Other, "general SOCKS server failure")),
+        2 => {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      "connection not allowed by ruleset"))
+        }
+        3 => return Err(io::Error::new(io::ErrorKind::Other, "network unreachable")),
+        4 => return Err(io::Error::new(io::ErrorKind::Other, "host unreachable")),
+        5 => return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused")),
+        6 => return Err(io::Error::new(io::ErrorKind::Other, "TTL expired")),
+        7 => return Err(io::Error::new(io::ErrorKind::Other, "command not supported")),
+        8 => return Err(io::Error::new(io::ErrorKind::Other, "address kind not supported")),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response code")),
+    }
+
+    read_address(socket)
+}
+
+/// A SOCKS5 client.
+#[derive(Debug)]
+pub struct Socks5Stream {
+    socket: TcpStream,
+    proxy_addr: TargetAddr,
+}
+
+impl Socks5Stream {
+    /// Connects to a target server through a SOCKS5 proxy.
+    pub fn connect<T, U>(proxy: T, target: U) -> io::Result<Socks5Stream>
+        where T: ToSocketAddrs,
+              U: ToTargetAddr
+    {
+        Self::connect_raw(CMD_CONNECT, proxy, target, &Authentication::None)
+    }
+
+    /// Connects to a target server through a SOCKS5 proxy using username/password
+    /// authentication.
+    pub fn connect_password<T, U>(proxy: T,
+                                  target: U,
+                                  username: &str,
+                                  password: &str)
+                                  -> io::Result<Socks5Stream>
+        where T: ToSocketAddrs,
+              U: ToTargetAddr
+    {
+        Self::connect_raw(CMD_CONNECT,
+                          proxy,
+                          target,
+                          &Authentication::Password {
+                              username: username,
+                              password: password,
+                          })
+    }
+
+    fn connect_raw<T, U>(command: u8,
+                        proxy: T,
+                        target: U,
+                        auth: &Authentication)
+                        -> io::Result<Socks5Stream>
+        where T: ToSocketAddrs,
+              U: ToTargetAddr
+    {
+        let mut socket = TcpStream::connect(proxy)?;
+        let proxy_addr = request(&mut socket, command, target, auth)?;
+
+        Ok(Socks5Stream {
+            socket: socket,
+            proxy_addr: proxy_addr,
+        })
+    }
+
+    /// Returns the proxy-side address of the connection between the proxy and
+    /// target server.
+    pub fn proxy_addr(&self) -> &TargetAddr {
+        &self.proxy_addr
+    }
+
+    /// Returns a shared reference to the inner `TcpStream`.
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    /// Returns a mutable reference to the inner `TcpStream`.
+    pub fn get_mut(&mut self) -> &mut TcpStream {
+        &mut self.socket
+    }
+
+    /// Consumes the `Socks5Stream`, returning the inner `TcpStream`.
+    pub fn into_inner(self) -> TcpStream {
+        self.socket
+    }
+}
+
+impl Read for Socks5Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.read(buf)
+    }
+}
+
+impl<'a> Read for &'a Socks5Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&self.socket).read(buf)
+    }
+}
+
+impl Write for Socks5Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
+
+impl<'a> Write for &'a Socks5Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&self.socket).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&self.socket).flush()
+    }
+}
+
+/// A UDP socket that sends and receives datagrams through a SOCKS5 proxy's
+/// UDP ASSOCIATE relay.
+///
+/// The TCP control connection used to establish the association is kept
+/// alive for the lifetime of the `Socks5Datagram`; dropping it tears down
+/// the relay on the proxy side, so it is stored alongside the `UdpSocket`
+/// rather than discarded after the handshake.
+#[derive(Debug)]
+pub struct Socks5Datagram {
+    socket: UdpSocket,
+    stream: Socks5Stream,
+    proxy_addr: SocketAddr,
+}
+
+impl Socks5Datagram {
+    /// Associates a new UDP socket with a SOCKS5 proxy.
+    ///
+    /// `addr` is the local address the datagram socket will bind to, e.g.
+    /// `0.0.0.0:0` to let the OS pick an ephemeral port. It is not sent to
+    /// the proxy: the ASSOCIATE request's DST.ADDR/DST.PORT is always the
+    /// unspecified address, since the client's source port isn't known
+    /// until the socket sends its first datagram.
+    pub fn bind<T, U>(proxy: T, addr: U) -> io::Result<Socks5Datagram>
+        where T: ToSocketAddrs,
+              U: ToSocketAddrs
+    {
+        let socket = UdpSocket::bind(addr)?;
+
+        // The DST.ADDR/DST.PORT of the ASSOCIATE request is supposed to be
+        // the address the client will send from, which isn't known until
+        // the socket above sends its first datagram -- send the unspecified
+        // address instead, as most proxies expect.
+        let unspecified = if socket.local_addr()?.is_ipv4() {
+            TargetAddr::Ip(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)))
+        } else {
+            TargetAddr::Ip(SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from([0; 16]), 0, 0, 0)))
+        };
+
+        let stream = Socks5Stream::connect_raw(CMD_UDP_ASSOCIATE,
+                                                     proxy,
+                                                     unspecified,
+                                                     &Authentication::None)?;
+
+        let mut proxy_addr = match stream.proxy_addr {
+            TargetAddr::Ip(addr) => addr,
+            TargetAddr::Domain(..) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "proxy returned a domain name for the UDP relay address"));
+            }
+        };
+
+        // Many proxies (e.g. Dante) reply with an unspecified BND.ADDR,
+        // meaning "the same host you sent the TCP control connection to".
+        if proxy_addr.ip().is_unspecified() {
+            let control_ip = stream.get_ref().peer_addr()?.ip();
+            proxy_addr = SocketAddr::new(control_ip, proxy_addr.port());
+        }
+
+        Ok(Socks5Datagram {
+            socket: socket,
+            stream: stream,
+            proxy_addr: proxy_addr,
+        })
+    }
+
+    /// Sends a datagram to `target` through the proxy's UDP relay.
+    pub fn send_to<U>(&self, buf: &[u8], target: U) -> io::Result<usize>
+        where U: ToTargetAddr
+    {
+        let target = target.to_target_addr()?;
+
+        let mut packet = vec![];
+        let _ = packet.write_u16::<BigEndian>(0); // reserved
+        let _ = packet.write_u8(0); // fragment number -- fragmentation is unsupported
+        write_address(&mut packet, &target)?;
+        packet.extend(buf);
+
+        self.socket.send_to(&packet, self.proxy_addr)?;
+        Ok(buf.len())
+    }
+
+    /// Receives a datagram relayed through the proxy, returning the number of
+    /// bytes read and the address it was originally sent from.
+    ///
+    /// Fragmented datagrams (fragment byte != 0) are rejected as
+    /// unsupported.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, TargetAddr)> {
+        let mut packet = vec![0; buf.len() + 262]; // header plus room for a full domain name
+        let (len, from) = self.socket.recv_from(&mut packet)?;
+        if from != self.proxy_addr {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      "received a datagram from an address other than the proxy relay"));
+        }
+        let mut packet = &packet[..len];
+
+        let _ = packet.read_u16::<BigEndian>()?; // reserved
+        if packet.read_u8()? != 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "fragmented datagrams are not supported"));
+        }
+
+        let from = read_address(&mut packet)?;
+
+        if packet.len() > buf.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "datagram payload too large for buffer"));
+        }
+        buf[..packet.len()].copy_from_slice(packet);
+
+        Ok((packet.len(), from))
+    }
+
+    /// Returns a shared reference to the TCP control connection backing this
+    /// association.
+    pub fn get_ref(&self) -> &Socks5Stream {
+        &self.stream
+    }
+}